@@ -1,16 +1,44 @@
+use base64::{engine::general_purpose::STANDARD as BASE64_STANDARD, Engine as _};
+use futures::StreamExt;
 use genai::adapter::AdapterKind;
-use genai::chat::{ChatMessage, ChatRequest};
+use genai::chat::{
+    ChatMessage, ChatRequest, ChatRole, ChatStreamEvent, ContentPart, MessageContent,
+    Tool as GenaiTool, ToolCall, ToolResponse,
+};
 use genai::resolver::{AuthData, Endpoint, ServiceTargetResolver};
 use genai::{Client, ModelIden, ServiceTarget};
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
+use std::collections::HashMap;
 use std::fmt::{self, Display, Write};
 use std::fs;
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::{cell::RefCell, env};
+use steel::rvals::SteelVal;
 use steel::steel_vm::engine::Engine;
 use steel::steel_vm::register_fn::RegisterFn;
 use steel_derive::Steel;
 
+/// Handle to a Steel engine. `Engine` is cheap to `.clone()` and shares its
+/// global bindings (tool/session/role registrations, etc.) across clones, so
+/// a closure that needs to call back into the interpreter from inside a
+/// native fn — e.g. `prompt` invoking a tool's `handler` while it is itself
+/// being dispatched from an in-flight `engine.run(...)` further up the stack
+/// — clones its own handle and drives that instead of trying to re-borrow
+/// the `Engine` value the caller is already running. That keeps every
+/// reentrant call a distinct Rust value with its own ordinary `&mut self`,
+/// so there's no `RefCell` to panic and no aliased `&mut` to reason about.
+type EngineHandle = Engine;
+
+/// Default cap on model/tool round-trips in a single `prompt` call, to
+/// avoid an infinite tool-calling loop. Overridable at runtime via
+/// `configure-max-steps` (see `MAX_STEPS`).
+const DEFAULT_MAX_STEPS: usize = 8;
+
+#[derive(Clone)]
 struct ModelConfig {
     url: String,
     token: String,
@@ -18,6 +46,40 @@ struct ModelConfig {
     adapter: String,
 }
 
+/// One `[[client]]` entry in `config.toml`. `token` may be a literal value
+/// or an `env:VARNAME` reference resolved via `lookup_env`.
+#[derive(serde::Deserialize)]
+struct ClientFileEntry {
+    name: String,
+    adapter: String,
+    url: String,
+    model: String,
+    token: String,
+}
+
+/// Shape of `dirs::config_dir()/feldspar/config.toml`.
+#[derive(serde::Deserialize, Default)]
+struct ClientConfigFile {
+    default: Option<String>,
+    #[serde(default)]
+    client: Vec<ClientFileEntry>,
+}
+
+/// One `[[role]]` entry in `roles.toml`/`roles.yaml`: a named system prompt,
+/// optionally containing a `{{input}}` placeholder for the user's text.
+#[derive(serde::Deserialize, Clone)]
+struct RoleFileEntry {
+    name: String,
+    prompt: String,
+}
+
+/// Shape of `roles.toml`/`roles.yaml`.
+#[derive(serde::Deserialize, Default)]
+struct RoleConfigFile {
+    #[serde(default)]
+    role: Vec<RoleFileEntry>,
+}
+
 #[derive(Clone, Debug, PartialEq, Steel)]
 enum ToolSchema {
     Number,
@@ -72,12 +134,97 @@ impl Tool {
     }
 }
 
+thread_local! {
+    static TOOL_REGISTRY: RefCell<Vec<Tool>> = RefCell::new(Vec::new());
+}
+
+fn register_tool(tool: Tool) {
+    TOOL_REGISTRY.with(|registry| registry.borrow_mut().push(tool));
+}
+
+fn find_tool(name: &str) -> Option<Tool> {
+    TOOL_REGISTRY.with(|registry| registry.borrow().iter().find(|t| t.name == name).cloned())
+}
+
+/// Build the JSON schema genai expects for a tool's parameters from the
+/// Steel-facing `(name, ToolSchema)` pairs declared via `tool/new`.
+fn tool_params_schema(schema: &[(String, ToolSchema)]) -> serde_json::Value {
+    let mut properties = serde_json::Map::new();
+    let mut required = Vec::new();
+    for (name, kind) in schema {
+        let json_ty = match kind {
+            ToolSchema::Number => "number",
+            ToolSchema::String => "string",
+            ToolSchema::Bool => "boolean",
+        };
+        properties.insert(name.clone(), serde_json::json!({ "type": json_ty }));
+        required.push(name.clone());
+    }
+    serde_json::json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn genai_tools() -> Vec<GenaiTool> {
+    TOOL_REGISTRY.with(|registry| {
+        registry
+            .borrow()
+            .iter()
+            .map(|tool| {
+                GenaiTool::new(tool.name.clone())
+                    .with_description(tool.description.clone())
+                    .with_schema(tool_params_schema(&tool.schema))
+            })
+            .collect()
+    })
+}
+
+/// Convert a tool's JSON call arguments into the Steel values its `handler`
+/// procedure expects, in declared schema order.
+fn steel_args_for_call(tool: &Tool, args: &serde_json::Value) -> Vec<SteelVal> {
+    tool.schema
+        .iter()
+        .map(|(name, kind)| {
+            let value = args.get(name).cloned().unwrap_or(serde_json::Value::Null);
+            match kind {
+                ToolSchema::Number => SteelVal::NumV(value.as_f64().unwrap_or_default()),
+                ToolSchema::String => {
+                    SteelVal::StringV(value.as_str().unwrap_or_default().into())
+                }
+                ToolSchema::Bool => SteelVal::BoolV(value.as_bool().unwrap_or_default()),
+            }
+        })
+        .collect()
+}
+
+/// Invoke a registered tool's Steel `handler` and serialize its return value
+/// back to a string for the tool-result message.
+///
+/// `prompt` (and so this) is itself dispatched from a native-fn call inside
+/// some other `Engine::run`/`call_function_by_name_with_args` call further
+/// up the stack. Rather than reaching back into that live `Engine` value —
+/// which is still mid-evaluation and has nothing free to lend — this clones
+/// `engine` (see `EngineHandle`) and calls the handler on the clone, a
+/// distinct value the clone owns outright, so a tool handler that itself
+/// calls `(prompt ...)` nests cleanly instead of aliasing the same `&mut`.
+fn invoke_tool(engine: &EngineHandle, tool: &Tool, call: &ToolCall) -> String {
+    let args = steel_args_for_call(tool, &call.fn_arguments);
+    let mut callee = engine.clone();
+    match callee.call_function_by_name_with_args(&tool.handler, args) {
+        Ok(result) => result.to_string(),
+        Err(e) => format!("Error calling tool `{}`: {}", tool.handler, e),
+    }
+}
+
 fn register_std_tool(engine: &mut Engine) {
     // Tool types and functions
     engine
         .register_type::<Tool>("tool")
         .register_fn("tool/new", Tool::new)
-        .register_fn("tool/describe", Tool::describe);
+        .register_fn("tool/describe", Tool::describe)
+        .register_fn("tool/register", register_tool);
 
     engine
         .register_type::<ToolSchema>("tool-schema")
@@ -103,6 +250,315 @@ thread_local! {
     );
     // I think this shouldn't be like this
     static MODEL_CONFIG: RefCell<ModelConfig> = RefCell::new(ModelConfig::default());
+    static STREAM_BY_DEFAULT: RefCell<bool> = RefCell::new(false);
+    // Cap on model/tool round-trips in a single `prompt` call, settable via
+    // `configure-max-steps`. Starts at `DEFAULT_MAX_STEPS`.
+    static MAX_STEPS: RefCell<usize> = RefCell::new(DEFAULT_MAX_STEPS);
+    // Named conversation histories, keyed by session name, plus which one (if
+    // any) `prompt` should read from and append to.
+    static SESSIONS: RefCell<HashMap<String, Vec<(String, String)>>> = RefCell::new(HashMap::new());
+    static ACTIVE_SESSION: RefCell<Option<String>> = RefCell::new(None);
+    // Named clients loaded from config.toml, plus which one `prompt` reads.
+    static CLIENTS: RefCell<HashMap<String, ModelConfig>> = RefCell::new(HashMap::new());
+    static ACTIVE_CLIENT: RefCell<Option<String>> = RefCell::new(None);
+    // Named system-prompt presets loaded from roles.toml/roles.yaml, plus
+    // which one (if any) `prompt` should inject.
+    static ROLES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    static ACTIVE_ROLE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Load `roles.toml` (preferred) or `roles.yaml` from the feldspar config
+/// dir into the `ROLES` registry. Does nothing if neither file exists.
+fn load_roles_config() {
+    let Some(dir) = dirs::config_dir().map(|p| p.join("feldspar")) else {
+        return;
+    };
+
+    let toml_path = dir.join("roles.toml");
+    let yaml_path = dir.join("roles.yaml");
+
+    let roles = if let Ok(contents) = fs::read_to_string(&toml_path) {
+        match toml::from_str::<RoleConfigFile>(&contents) {
+            Ok(cfg) => cfg.role,
+            Err(e) => {
+                eprintln!("Error parsing {}: {}", toml_path.display(), e);
+                Vec::new()
+            }
+        }
+    } else if let Ok(contents) = fs::read_to_string(&yaml_path) {
+        match serde_yaml::from_str::<RoleConfigFile>(&contents) {
+            Ok(cfg) => cfg.role,
+            Err(e) => {
+                eprintln!("Error parsing {}: {}", yaml_path.display(), e);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    ROLES.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        for entry in roles {
+            registry.insert(entry.name, entry.prompt);
+        }
+    });
+}
+
+fn role_use(name: String) {
+    let exists = ROLES.with(|r| r.borrow().contains_key(&name));
+    if exists {
+        ACTIVE_ROLE.with(|a| *a.borrow_mut() = Some(name.clone()));
+        println!("Using role: {}", name);
+    } else {
+        eprintln!("Unknown role `{}`. Use :roles to see configured roles.", name);
+    }
+}
+
+fn role_list() -> Vec<String> {
+    ROLES.with(|r| r.borrow().keys().cloned().collect())
+}
+
+/// Substitute the `{{input}}` placeholder (if present) in a role's prompt
+/// with the user's text, so the role can wrap it for task-specific personas.
+fn render_role_prompt(role_prompt: &str, user_text: &str) -> String {
+    role_prompt.replace("{{input}}", user_text)
+}
+
+fn active_role_system_message(user_prompt: &str) -> Option<String> {
+    ACTIVE_ROLE
+        .with(|a| a.borrow().clone())
+        .and_then(|name| ROLES.with(|r| r.borrow().get(&name).cloned()))
+        .map(|role_prompt| render_role_prompt(&role_prompt, user_prompt))
+}
+
+fn client_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|p| p.join("feldspar").join("config.toml"))
+}
+
+fn resolve_token(raw: &str) -> String {
+    match raw.strip_prefix("env:") {
+        Some(var) => lookup_env(var.to_string()).unwrap_or_else(|e| {
+            eprintln!("Warning: could not resolve token env var `{}`: {}", var, e);
+            String::new()
+        }),
+        None => raw.to_string(),
+    }
+}
+
+/// Load `config.toml`'s named clients into the `CLIENTS` registry and
+/// activate its `default` (or the first declared client, if any).
+fn load_client_config() {
+    let Some(path) = client_config_path() else {
+        return;
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return;
+    };
+    let parsed: ClientConfigFile = match toml::from_str(&contents) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Error parsing {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    // Take the fallback name from the file's declaration order before
+    // `parsed.client` is consumed below — `CLIENTS` is a `HashMap`, so
+    // picking from its keys instead would make "no `default` set" resolve
+    // to a randomly-seeded, non-deterministic client across runs.
+    let first_declared = parsed.client.first().map(|entry| entry.name.clone());
+
+    CLIENTS.with(|clients| {
+        let mut clients = clients.borrow_mut();
+        for entry in parsed.client {
+            clients.insert(
+                entry.name.clone(),
+                ModelConfig {
+                    url: entry.url,
+                    token: resolve_token(&entry.token),
+                    model: entry.model,
+                    adapter: entry.adapter,
+                },
+            );
+        }
+    });
+
+    let default_name = parsed.default.or(first_declared);
+    if let Some(name) = default_name {
+        if CLIENTS.with(|c| c.borrow().contains_key(&name)) {
+            ACTIVE_CLIENT.with(|a| *a.borrow_mut() = Some(name));
+        }
+    }
+}
+
+fn use_client(name: String) {
+    let exists = CLIENTS.with(|c| c.borrow().contains_key(&name));
+    if exists {
+        ACTIVE_CLIENT.with(|a| *a.borrow_mut() = Some(name.clone()));
+        println!("Using client: {}", name);
+    } else {
+        eprintln!(
+            "Unknown client `{}`. Use :models to list configured clients.",
+            name
+        );
+    }
+}
+
+/// Resolve the config `prompt`/`prompt-stream` should use: the active named
+/// client if one is set, falling back to the legacy single `MODEL_CONFIG`
+/// (still reachable via `configure-model`) otherwise.
+fn active_model_config() -> ModelConfig {
+    ACTIVE_CLIENT
+        .with(|a| a.borrow().clone())
+        .and_then(|name| CLIENTS.with(|c| c.borrow().get(&name).cloned()))
+        .unwrap_or_else(|| MODEL_CONFIG.with(|config| config.borrow().clone()))
+}
+
+fn sessions_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|p| p.join("feldspar").join("sessions"))
+}
+
+fn session_path(name: &str) -> Option<PathBuf> {
+    sessions_dir().map(|dir| dir.join(format!("{}.json", name)))
+}
+
+fn load_session_from_disk(name: &str) -> Vec<(String, String)> {
+    session_path(name)
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_session_to_disk(name: &str) {
+    let turns = SESSIONS.with(|s| s.borrow().get(name).cloned().unwrap_or_default());
+
+    let Some(dir) = sessions_dir() else {
+        eprintln!("Error: could not determine feldspar data directory");
+        return;
+    };
+    if let Err(e) = fs::create_dir_all(&dir) {
+        eprintln!("Error creating session directory: {}", e);
+        return;
+    }
+
+    let path = dir.join(format!("{}.json", name));
+    match serde_json::to_string_pretty(&turns) {
+        Ok(json) => {
+            if let Err(e) = fs::write(&path, json) {
+                eprintln!("Error saving session `{}`: {}", name, e);
+            }
+        }
+        Err(e) => eprintln!("Error serializing session `{}`: {}", name, e),
+    }
+}
+
+/// Switch the active session to `name`, creating it if new and lazily
+/// loading its saved history from disk the first time it's used.
+fn session_use(name: String) {
+    SESSIONS.with(|s| {
+        let mut sessions = s.borrow_mut();
+        if !sessions.contains_key(&name) {
+            let history = load_session_from_disk(&name);
+            sessions.insert(name.clone(), history);
+        }
+    });
+    ACTIVE_SESSION.with(|a| *a.borrow_mut() = Some(name));
+}
+
+fn session_new(name: String) {
+    SESSIONS.with(|s| {
+        s.borrow_mut().insert(name.clone(), Vec::new());
+    });
+    ACTIVE_SESSION.with(|a| *a.borrow_mut() = Some(name));
+}
+
+fn session_append(role: String, content: String) {
+    ACTIVE_SESSION.with(|a| {
+        if let Some(name) = a.borrow().clone() {
+            SESSIONS.with(|s| {
+                s.borrow_mut()
+                    .entry(name)
+                    .or_insert_with(Vec::new)
+                    .push((role, content));
+            });
+        }
+    });
+}
+
+fn session_clear() {
+    ACTIVE_SESSION.with(|a| {
+        if let Some(name) = a.borrow().clone() {
+            SESSIONS.with(|s| {
+                s.borrow_mut().insert(name, Vec::new());
+            });
+        }
+    });
+}
+
+fn active_session_history() -> Vec<(String, String)> {
+    ACTIVE_SESSION.with(|a| {
+        a.borrow()
+            .clone()
+            .and_then(|name| SESSIONS.with(|s| s.borrow().get(&name).cloned()))
+            .unwrap_or_default()
+    })
+}
+
+fn register_std_session(engine: &mut Engine) {
+    engine
+        .register_fn("session/new", session_new)
+        .register_fn("session/use", session_use)
+        .register_fn("session/append", session_append)
+        .register_fn("session/clear", session_clear);
+}
+
+/// Process-wide Ctrl-C signal. `ctrlc::set_handler` can only ever be
+/// installed once per process (later calls just return the same handler),
+/// so once any path has called this — streaming or blocking — Ctrl-C stops
+/// killing the process for the rest of the run; every path that can hang
+/// must poll the flag itself instead of relying on the default SIGINT
+/// disposition. `prompt_stream` checks it between stream chunks; `prompt`'s
+/// tool loop and `prompt_with_media` race it against their request via
+/// `run_interruptible` so a hung or dead endpoint stays killable with Ctrl-C
+/// no matter which path ran first.
+fn abort_signal() -> &'static Arc<AtomicBool> {
+    static SIGNAL: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    SIGNAL.get_or_init(|| {
+        let flag = Arc::new(AtomicBool::new(false));
+        let handler_flag = flag.clone();
+        if let Err(e) = ctrlc::set_handler(move || {
+            handler_flag.store(true, Ordering::SeqCst);
+        }) {
+            eprintln!("Warning: failed to install Ctrl-C handler: {}", e);
+        }
+        flag
+    })
+}
+
+/// How often `run_interruptible` checks `abort_signal()` while a blocking
+/// request is in flight.
+const ABORT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Drive `fut` to completion, but bail out early with `None` if Ctrl-C fires
+/// first (checked every `ABORT_POLL_INTERVAL`). Used by `prompt` and
+/// `prompt_with_media` to stay interruptible the same way `prompt_stream` is,
+/// even though a single `exec_chat` call has no chunks to check between.
+async fn run_interruptible<T>(fut: impl std::future::Future<Output = T>) -> Option<T> {
+    let abort = abort_signal();
+    abort.store(false, Ordering::SeqCst);
+
+    tokio::pin!(fut);
+    loop {
+        tokio::select! {
+            result = &mut fut => return Some(result),
+            _ = tokio::time::sleep(ABORT_POLL_INTERVAL) => {
+                if abort.load(Ordering::SeqCst) {
+                    return None;
+                }
+            }
+        }
+    }
 }
 
 fn adapter_from_string(s: &str) -> AdapterKind {
@@ -117,22 +573,15 @@ fn adapter_from_string(s: &str) -> AdapterKind {
     }
 }
 
-/// Prompt with history. Takes a list of (role, content) pairs as Vec<Vec<String>>
-/// where each inner vec is ["user"|"assistant"|"system", "content"]
-fn prompt(history: Vec<Vec<String>>, user_prompt: String) -> String {
-    let (url, token, model, adapter) = MODEL_CONFIG.with(|config| {
-        let c = config.borrow();
-        (
-            c.url.clone(),
-            c.token.clone(),
-            c.model.clone(),
-            c.adapter.clone(),
-        )
-    });
-
-    let adapter_kind = adapter_from_string(&adapter);
+/// Build a `genai` `Client` that routes every request at `config`'s
+/// endpoint/token/adapter, shared by `prompt`, `prompt_stream`, and
+/// `prompt_with_media` so a future change to the resolver only needs to
+/// happen once.
+fn build_client(config: &ModelConfig) -> Client {
+    let url = config.url.clone();
+    let token = config.token.clone();
+    let adapter_kind = adapter_from_string(&config.adapter);
 
-    // Create resolver for custom endpoint
     let target_resolver =
         ServiceTargetResolver::from_resolver_fn(move |service_target: ServiceTarget| {
             Ok(ServiceTarget {
@@ -142,43 +591,353 @@ fn prompt(history: Vec<Vec<String>>, user_prompt: String) -> String {
             })
         });
 
-    let client = Client::builder()
+    Client::builder()
         .with_service_target_resolver(target_resolver)
-        .build();
+        .build()
+}
+
+/// Convert the Steel-facing `["user"|"assistant"|"system", content]` history
+/// pairs into `genai` chat messages, shared by `prompt`, `prompt_stream`, and
+/// `prompt_with_media`. Entries with an unrecognized role, or fewer than two
+/// elements, are dropped.
+fn history_to_messages(history: &[Vec<String>]) -> Vec<ChatMessage> {
+    history
+        .iter()
+        .filter_map(|entry| {
+            if entry.len() >= 2 {
+                let role = &entry[0];
+                let content = &entry[1];
+                match role.as_str() {
+                    "user" => Some(ChatMessage::user(content)),
+                    "assistant" => Some(ChatMessage::assistant(content)),
+                    "system" => Some(ChatMessage::system(content)),
+                    _ => None,
+                }
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Prepend the active session's saved history (if any) ahead of the
+/// caller-supplied `history`, shared by `prompt`, `prompt_stream`, and
+/// `prompt_with_media`.
+fn with_session_history(mut history: Vec<Vec<String>>) -> Vec<Vec<String>> {
+    let mut full_history: Vec<Vec<String>> = active_session_history()
+        .into_iter()
+        .map(|(role, content)| vec![role, content])
+        .collect();
+    full_history.append(&mut history);
+    full_history
+}
+
+/// Prompt with history. Takes a list of (role, content) pairs as Vec<Vec<String>>
+/// where each inner vec is ["user"|"assistant"|"system", "content"]
+///
+/// If any tools are registered via `tool/register`, this drives a full
+/// agentic loop: the model may respond with tool calls instead of text, in
+/// which case the matching Steel handlers are invoked and their results are
+/// fed back until the model gives a plain text answer or the round-trip cap
+/// (`DEFAULT_MAX_STEPS`, overridable via `configure-max-steps`) is exceeded.
+/// Each round-trip runs through `run_interruptible`, so Ctrl-C against a hung
+/// or dead endpoint stops it the same way it stops `prompt_stream`.
+fn prompt(engine: &EngineHandle, history: Vec<Vec<String>>, user_prompt: String) -> String {
+    if STREAM_BY_DEFAULT.with(|s| *s.borrow()) {
+        return prompt_stream(history, user_prompt);
+    }
+
+    let config = active_model_config();
+    let model = config.model.clone();
+    let client = build_client(&config);
+
+    let tools = genai_tools();
+
+    let history = with_session_history(history);
+
+    let user_prompt_for_session = user_prompt.clone();
+    let role_system = active_role_system_message(&user_prompt);
 
     TOKIO_RT.with(|rt| {
         rt.borrow().block_on(async {
+            // The active role's system prompt, if any, leads the conversation.
+            let mut messages: Vec<ChatMessage> = Vec::new();
+            if let Some(role_text) = role_system {
+                messages.push(ChatMessage::system(role_text));
+            }
+
             // Build messages from history
-            let mut messages: Vec<ChatMessage> = history
-                .iter()
-                .filter_map(|entry| {
-                    if entry.len() >= 2 {
-                        let role = &entry[0];
-                        let content = &entry[1];
-                        match role.as_str() {
-                            "user" => Some(ChatMessage::user(content)),
-                            "assistant" => Some(ChatMessage::assistant(content)),
-                            "system" => Some(ChatMessage::system(content)),
-                            _ => None,
-                        }
-                    } else {
-                        None
-                    }
-                })
-                .collect();
+            messages.extend(history_to_messages(&history));
 
             // Add the new user prompt
             messages.push(ChatMessage::user(user_prompt));
 
+            let mut step = 0usize;
+            let steps_cap = max_steps();
+            let (had_error, outcome) = loop {
+                let mut chat_req = ChatRequest::new(messages.clone());
+                if !tools.is_empty() {
+                    chat_req = chat_req.with_tools(tools.clone());
+                }
+
+                let response = match run_interruptible(client.exec_chat(&model, chat_req, None)).await {
+                    Some(Ok(response)) => response,
+                    Some(Err(e)) => break (true, format!("Error: {}", e)),
+                    None => break (true, "Error: interrupted (Ctrl-C)".to_string()),
+                };
+
+                let tool_calls = response.tool_calls();
+                if tool_calls.is_empty() {
+                    let text = response
+                        .content_text_as_str()
+                        .unwrap_or("No response")
+                        .to_string();
+                    break (false, text);
+                }
+
+                if step >= steps_cap {
+                    break (
+                        true,
+                        format!("Error: exceeded max tool-call steps ({})", steps_cap),
+                    );
+                }
+                step += 1;
+
+                messages.push(ChatMessage {
+                    role: ChatRole::Assistant,
+                    content: MessageContent::ToolCalls(tool_calls.clone()),
+                    options: None,
+                });
+
+                let mut tool_responses = Vec::new();
+                for call in &tool_calls {
+                    let result = match find_tool(&call.fn_name) {
+                        Some(tool) => invoke_tool(engine, &tool, call),
+                        None => format!("Error: unknown tool `{}`", call.fn_name),
+                    };
+                    tool_responses.push(ToolResponse::new(call.call_id.clone(), result));
+                }
+                messages.push(ChatMessage {
+                    role: ChatRole::Tool,
+                    content: MessageContent::ToolResponses(tool_responses),
+                    options: None,
+                });
+            };
+
+            if !had_error {
+                session_append("user".to_string(), user_prompt_for_session);
+                session_append("assistant".to_string(), outcome.clone());
+            }
+
+            outcome
+        })
+    })
+}
+
+/// Like `prompt`, but streams the reply to stdout as it arrives instead of
+/// blocking until the full response is assembled, returning the full
+/// accumulated text. A Ctrl-C during streaming sets the process-wide
+/// `abort_signal`, which is checked between chunks; an interrupted
+/// generation returns the partial text collected so far rather than erroring.
+///
+/// Honors the active session (prepending/appending its history, same as
+/// `prompt`) and the active role (injected as a leading system message), but
+/// does not support registered tools: a tool-calling round-trip needs the
+/// full response before it can act on it, which defeats token-by-token
+/// streaming, so this returns an error instead of silently ignoring them.
+fn prompt_stream(history: Vec<Vec<String>>, user_prompt: String) -> String {
+    if !genai_tools().is_empty() {
+        return "Error: streaming does not support registered tools; use `prompt` \
+                with streaming disabled (configure-stream #f) instead."
+            .to_string();
+    }
+
+    let config = active_model_config();
+    let model = config.model.clone();
+    let client = build_client(&config);
+
+    let history = with_session_history(history);
+
+    let user_prompt_for_session = user_prompt.clone();
+    let role_system = active_role_system_message(&user_prompt);
+
+    TOKIO_RT.with(|rt| {
+        rt.borrow().block_on(async {
+            let mut messages: Vec<ChatMessage> = Vec::new();
+            if let Some(role_text) = role_system {
+                messages.push(ChatMessage::system(role_text));
+            }
+            messages.extend(history_to_messages(&history));
+            messages.push(ChatMessage::user(user_prompt));
+
+            let chat_req = ChatRequest::new(messages);
+
+            let abort = abort_signal();
+            abort.store(false, Ordering::SeqCst);
+
+            let mut stream_res = match client.exec_chat_stream(&model, chat_req, None).await {
+                Ok(res) => res,
+                Err(e) => return format!("Error: {}", e),
+            };
+
+            let mut full_text = String::new();
+            while let Some(event) = stream_res.stream.next().await {
+                if abort.load(Ordering::SeqCst) {
+                    println!("\n^C (generation interrupted)");
+                    break;
+                }
+                match event {
+                    Ok(ChatStreamEvent::Chunk(chunk)) => {
+                        print!("{}", chunk.content);
+                        let _ = std::io::stdout().flush();
+                        full_text.push_str(&chunk.content);
+                    }
+                    Ok(ChatStreamEvent::End(_)) => break,
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!();
+                        eprintln!("Error: {}", e);
+                        break;
+                    }
+                }
+            }
+            println!();
+
+            session_append("user".to_string(), user_prompt_for_session);
+            session_append("assistant".to_string(), full_text.clone());
+
+            full_text
+        })
+    })
+}
+
+/// Toggle whether `prompt` streams its reply by default, mirroring
+/// `configure-model` for the other connection settings. Streaming honors
+/// the active session and role but not registered tools (see
+/// `prompt_stream`'s doc comment).
+fn configure_stream(enabled: bool) {
+    STREAM_BY_DEFAULT.with(|s| *s.borrow_mut() = enabled);
+    println!("Streaming by default: {}", enabled);
+    if enabled && !genai_tools().is_empty() {
+        println!(
+            "Warning: registered tools are not supported while streaming; \
+             `prompt` will return an error until streaming is disabled."
+        );
+    }
+}
+
+/// Raise or lower the cap `prompt`'s agentic loop places on model/tool
+/// round-trips in a single call, overriding `DEFAULT_MAX_STEPS`.
+fn configure_max_steps(steps: usize) {
+    MAX_STEPS.with(|s| *s.borrow_mut() = steps);
+    println!("Max tool-call steps: {}", steps);
+}
+
+fn max_steps() -> usize {
+    MAX_STEPS.with(|s| *s.borrow())
+}
+
+/// Guess the image MIME type of a path from its extension. `None` means
+/// "treat as a plain text attachment" rather than "unsupported".
+fn image_mime_for_path(path: &str) -> Option<&'static str> {
+    let ext = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match ext.as_deref() {
+        Some("png") => Some("image/png"),
+        Some("jpg") | Some("jpeg") => Some("image/jpeg"),
+        Some("gif") => Some("image/gif"),
+        Some("webp") => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Build the user turn for `prompt-with-media`: image paths (and already-
+/// formed `data:` URLs) become image content parts, everything else is read
+/// as text and folded into the message text.
+fn build_user_message_with_media(text: String, media: Vec<String>) -> ChatMessage {
+    let mut image_parts = Vec::new();
+    let mut full_text = text;
+
+    for path in media {
+        if path.starts_with("data:") {
+            image_parts.push(ContentPart::from_image_url(path));
+            continue;
+        }
+
+        if let Some(mime) = image_mime_for_path(&path) {
+            match fs::read(&path) {
+                Ok(bytes) => {
+                    let data_url = format!("data:{};base64,{}", mime, BASE64_STANDARD.encode(bytes));
+                    image_parts.push(ContentPart::from_image_url(data_url));
+                }
+                Err(e) => eprintln!("Error reading image `{}`: {}", path, e),
+            }
+        } else {
+            match fs::read_to_string(&path) {
+                Ok(contents) => {
+                    full_text.push('\n');
+                    full_text.push_str(&contents);
+                }
+                Err(e) => eprintln!("Error reading file `{}`: {}", path, e),
+            }
+        }
+    }
+
+    if image_parts.is_empty() {
+        return ChatMessage::user(full_text);
+    }
+
+    let mut parts = vec![ContentPart::from_text(full_text)];
+    parts.extend(image_parts);
+    ChatMessage::user(MessageContent::Parts(parts))
+}
+
+/// Like `prompt`, but lets a Steel script attach local files (or `data:`
+/// URLs) to the user turn so vision-capable models can see them. Runs its
+/// request through `run_interruptible`, so Ctrl-C against a hung or dead
+/// endpoint stops it rather than silently doing nothing.
+fn prompt_with_media(history: Vec<Vec<String>>, user_prompt: String, media: Vec<String>) -> String {
+    let config = active_model_config();
+    let model = config.model.clone();
+    let client = build_client(&config);
+
+    let history = with_session_history(history);
+
+    let user_prompt_for_session = user_prompt.clone();
+    let role_system = active_role_system_message(&user_prompt);
+    let user_message = build_user_message_with_media(user_prompt, media);
+
+    TOKIO_RT.with(|rt| {
+        rt.borrow().block_on(async {
+            let mut messages: Vec<ChatMessage> = Vec::new();
+            if let Some(role_text) = role_system {
+                messages.push(ChatMessage::system(role_text));
+            }
+            messages.extend(history_to_messages(&history));
+            messages.push(user_message);
+
             let chat_req = ChatRequest::new(messages);
 
-            match client.exec_chat(&model, chat_req, None).await {
-                Ok(response) => response
-                    .content_text_as_str()
-                    .unwrap_or("No response")
-                    .to_string(),
-                Err(e) => format!("Error: {}", e),
+            let (had_error, outcome) = match run_interruptible(client.exec_chat(&model, chat_req, None)).await {
+                Some(Ok(response)) => (
+                    false,
+                    response
+                        .content_text_as_str()
+                        .unwrap_or("No response")
+                        .to_string(),
+                ),
+                Some(Err(e)) => (true, format!("Error: {}", e)),
+                None => (true, "Error: interrupted (Ctrl-C)".to_string()),
+            };
+
+            if !had_error {
+                session_append("user".to_string(), user_prompt_for_session);
+                session_append("assistant".to_string(), outcome.clone());
             }
+
+            outcome
         })
     })
 }
@@ -208,17 +967,36 @@ fn register_std_io(engine: &mut Engine) {
     engine.register_fn("lookup-env", lookup_env);
 }
 
-fn register_std_chat(engine: &mut Engine) {
-    engine.register_fn("prompt", prompt);
+fn register_std_chat(engine: &mut EngineHandle) {
+    let handle = engine.clone();
+    engine.register_fn("prompt", move |history: Vec<Vec<String>>, user_prompt: String| {
+        prompt(&handle, history, user_prompt)
+    });
     engine.register_fn("configure-model", configure_model);
+    engine.register_fn("prompt-stream", prompt_stream);
+    engine.register_fn("configure-stream", configure_stream);
+    engine.register_fn("configure-max-steps", configure_max_steps);
+    engine.register_fn("use-client", use_client);
+    engine.register_fn("prompt-with-media", prompt_with_media);
+}
+
+fn register_std_role(engine: &mut Engine) {
+    engine
+        .register_fn("role/use", role_use)
+        .register_fn("role/list", role_list);
 }
 
-fn init() -> Engine {
-    let mut engine = Engine::new_sandboxed();
+fn init() -> EngineHandle {
+    load_client_config();
+    load_roles_config();
+
+    let mut engine: EngineHandle = Engine::new_sandboxed();
 
     register_std_chat(&mut engine);
     register_std_io(&mut engine);
     register_std_tool(&mut engine);
+    register_std_session(&mut engine);
+    register_std_role(&mut engine);
 
     println!("Type :help for commands\n");
 
@@ -227,14 +1005,22 @@ fn init() -> Engine {
 
 fn print_help() {
     println!("Commands:");
-    println!("  :help         (:h)  Show this help");
-    println!("  :load <file>  (:l)  Load a .scm file");
-    println!("  :quit         (:q)  Exit the REPL");
+    println!("  :help              (:h)  Show this help");
+    println!("  :load <file>       (:l)  Load a .scm file");
+    println!("  :session <name>         Switch to (or create) a named session");
+    println!("  :sessions               List loaded sessions");
+    println!("  :save                   Save the active session to disk");
+    println!("  :load-session <name>    Load a session from disk and make it active");
+    println!("  :model <name>           Switch the active client (see config.toml)");
+    println!("  :models                 List configured clients");
+    println!("  :role <name>            Switch the active role (see roles.toml)");
+    println!("  :roles                  List configured roles");
+    println!("  :quit              (:q)  Exit the REPL");
     println!("Functions:");
     println!("  (chat <string>)                                    Prompt the AI");
 }
 
-fn handle_command(cmd: &str, engine: &mut Engine) -> Option<bool> {
+fn handle_command(cmd: &str, engine: &mut EngineHandle) -> Option<bool> {
     let parts: Vec<&str> = cmd[1..].splitn(2, ' ').collect();
     let command = parts[0];
     let arg = parts.get(1).map(|s| s.trim());
@@ -255,6 +1041,91 @@ fn handle_command(cmd: &str, engine: &mut Engine) -> Option<bool> {
                 Err(e) => eprintln!("Error reading {}: {}", path, e),
             }
         }
+        "session" => {
+            let Some(name) = arg else {
+                eprintln!("Usage: :session <name>");
+                return Some(false);
+            };
+            session_use(name.to_string());
+            println!("Active session: {}", name);
+        }
+        "sessions" => SESSIONS.with(|s| {
+            let sessions = s.borrow();
+            if sessions.is_empty() {
+                println!("No sessions loaded.");
+            } else {
+                for name in sessions.keys() {
+                    println!("  {}", name);
+                }
+            }
+        }),
+        "save" => match ACTIVE_SESSION.with(|a| a.borrow().clone()) {
+            Some(name) => {
+                save_session_to_disk(&name);
+                println!("Saved session `{}`", name);
+            }
+            None => eprintln!("No active session to save. Use :session <name> first."),
+        },
+        "load-session" => {
+            let Some(name) = arg else {
+                eprintln!("Usage: :load-session <name>");
+                return Some(false);
+            };
+            let loaded = load_session_from_disk(name);
+            SESSIONS.with(|s| {
+                s.borrow_mut().insert(name.to_string(), loaded);
+            });
+            ACTIVE_SESSION.with(|a| *a.borrow_mut() = Some(name.to_string()));
+            println!("Loaded session `{}`", name);
+        }
+        "model" => {
+            let Some(name) = arg else {
+                eprintln!("Usage: :model <name>");
+                return Some(false);
+            };
+            use_client(name.to_string());
+        }
+        "models" => {
+            let active = ACTIVE_CLIENT.with(|a| a.borrow().clone());
+            CLIENTS.with(|c| {
+                let clients = c.borrow();
+                if clients.is_empty() {
+                    println!("No clients configured. Add some to config.toml.");
+                } else {
+                    for name in clients.keys() {
+                        let marker = if active.as_deref() == Some(name.as_str()) {
+                            "*"
+                        } else {
+                            " "
+                        };
+                        println!("{} {}", marker, name);
+                    }
+                }
+            });
+        }
+        "role" => {
+            let Some(name) = arg else {
+                eprintln!("Usage: :role <name>");
+                return Some(false);
+            };
+            role_use(name.to_string());
+        }
+        "roles" => {
+            let active = ACTIVE_ROLE.with(|a| a.borrow().clone());
+            let roles = role_list();
+            if roles.is_empty() {
+                println!("No roles configured. Add some to roles.toml.");
+            } else {
+                for name in roles {
+                    let marker = if active.as_deref() == Some(name.as_str()) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{} {}", marker, name);
+                }
+            }
+        }
         _ => eprintln!(
             "Unknown command: {}. Type :help for available commands.",
             command
@@ -263,7 +1134,7 @@ fn handle_command(cmd: &str, engine: &mut Engine) -> Option<bool> {
     Some(false)
 }
 
-fn repl(mut engine: Engine) {
+fn repl(mut engine: EngineHandle) {
     let mut rl = DefaultEditor::new().expect("Failed to create editor");
 
     // Load history from file if it exists